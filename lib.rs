@@ -2,45 +2,149 @@
 
 #[ink::contract]
 mod sam_os {
+    use ink::env::hash::{Blake2x256, HashOutput};
     use ink::storage::Mapping;
     use scale_info::prelude::vec::Vec;
 
     type DID = Vec<u8>;
     type IpfsCid = Vec<u8>;
     type HashKey = u64;
-    type AuthContent = u64;
     type DbMetadata = Vec<u8>;
+    /// compressed secp256k1 public key
+    type PubKey = [u8; 33];
+    /// recoverable ECDSA signature (r, s, v)
+    type Signature = [u8; 65];
+
+    /// number of appended hash table operations between consolidated checkpoints
+    const CHECKPOINT_INTERVAL: u64 = 64;
+
+    /// capability bitflags for a grantee's rights over a file, WASI-filesystem
+    /// rights-set style
+    const RIGHT_READ: u64 = 1;
+    const RIGHT_WRITE: u64 = 2;
+    const RIGHT_SHARE: u64 = 4;
+
+    #[derive(scale::Decode, scale::Encode, Default, Clone)]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
+    )]
+    struct AccessGrant {
+        /// bitwise-or of `RIGHT_*` flags
+        rights: u64,
+        /// absolute expiry timestamp in ms, or `0` for no limit
+        expiry: u64,
+    }
 
     #[derive(scale::Decode, scale::Encode, Default)]
     #[cfg_attr(
         feature = "std",
-        derive(scale_info::TypeInfo, ink::storage::traits: :StorageLayout)
+        derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
     )]
     struct FileMeta {
-        access_list: [DID; 2],
+        /// DIDs that have been granted some level of access to this file
+        grantees: Vec<DID>,
         cid: IpfsCid,
         nonce: u64,
         db_meta: DbMetadata,
+        /// content-defined chunk hashes making up this file, in order; each is
+        /// looked up in the chunk registry for its CID
+        chunks: Vec<HashKey>,
     }
 
-    #[derive(scale::Decode, scale::Encode, Default, Clone)]
+    #[derive(scale::Decode, scale::Encode, Clone)]
     #[cfg_attr(
         feature = "std",
         derive(scale_info::TypeInfo, ink::storage::traits::StorageLayout)
     )]
     struct UserInfo {
-        /// authentication material
-        auth_content: AuthContent,
+        /// public key used to verify signed authentication challenges
+        auth_pubkey: PubKey,
+        /// monotonically increasing nonce, bumped on every successful authentication
+        /// to prevent replay of a previously signed challenge
+        auth_nonce: u64,
         /// uri of document describing the DID
         did_doc_cid: IpfsCid,
-        /// uri of the root hash table
-        root_hash_table: IpfsCid,
+        /// CID of the last consolidated checkpoint of the root hash table op log
+        checkpoint_cid: IpfsCid,
+        /// seq the checkpoint was taken at
+        checkpoint_seq: u64,
+        /// seq of the most recently appended root hash table operation
+        latest_seq: u64,
+    }
+
+    // `[u8; 33]` has no `Default` impl in stable Rust, so `PubKey` blocks a
+    // derived `Default` for `UserInfo` — implement it by hand instead.
+    impl Default for UserInfo {
+        fn default() -> Self {
+            Self {
+                auth_pubkey: [0u8; 33],
+                auth_nonce: 0,
+                did_doc_cid: Default::default(),
+                checkpoint_cid: Default::default(),
+                checkpoint_seq: 0,
+                latest_seq: 0,
+            }
+        }
+    }
+
+    /// Emitted when a new DID account is created
+    #[ink(event)]
+    pub struct AccountCreated {
+        #[ink(topic)]
+        did: DID,
+    }
+
+    /// Emitted whenever a file's metadata is created or updated
+    #[ink(event)]
+    pub struct FileUpdated {
+        #[ink(topic)]
+        hk: HashKey,
+        nonce: u64,
+        cid: IpfsCid,
+    }
+
+    /// Emitted whenever a grantee's rights on a file are set or changed
+    #[ink(event)]
+    pub struct AccessGranted {
+        #[ink(topic)]
+        did: DID,
+        #[ink(topic)]
+        hk: HashKey,
+        rights: u64,
+    }
+
+    /// Emitted when a grantee's access to a file is revoked
+    #[ink(event)]
+    pub struct AccessRevoked {
+        #[ink(topic)]
+        did: DID,
+        #[ink(topic)]
+        hk: HashKey,
+    }
+
+    /// Emitted whenever an operation is appended to a DID's root hash table op log
+    #[ink(event)]
+    pub struct HashTableUpdated {
+        #[ink(topic)]
+        did: DID,
+        cid: IpfsCid,
+        seq: u64,
     }
 
     /// Error types
     #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
     #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
-    pub enum Error {}
+    pub enum Error {
+        /// no account exists for the given DID
+        AccountNotFound,
+        /// the recovered public key does not match the one on record for the DID
+        InvalidSignature,
+        /// the caller does not hold the `SHARE` right needed for this operation
+        Unauthorized,
+        /// the grantee is not yet on the file's grantee list
+        GranteeNotFound,
+    }
 
     /// main storage structure for the SamaritanOS contract
     #[ink(storage)]
@@ -50,9 +154,14 @@ mod sam_os {
         /// Storage for data files metadata
         files_meta: Mapping<HashKey, FileMeta>,
         /// Storage for a DID and the files its allowed to access and their permissions
-        access_list: Mapping<(DID, HashKey), u64>,
+        access_list: Mapping<(DID, HashKey), AccessGrant>,
         /// List of file keys a DID has access to
         file_keys: Mapping<DID, Vec<HashKey>>,
+        /// Append-only log of root hash table operation CIDs, keyed by DID and seq
+        hashtable_ops: Mapping<(DID, u64), IpfsCid>,
+        /// Deduplicated chunk storage: each chunk hash maps to its CID and the
+        /// number of entries in files' chunk lists currently referencing it
+        chunk_registry: Mapping<HashKey, (IpfsCid, u64)>,
     }
 
     /// Shorten the result type
@@ -67,6 +176,8 @@ mod sam_os {
                 files_meta: Default::default(),
                 access_list: Default::default(),
                 file_keys: Default::default(),
+                hashtable_ops: Default::default(),
+                chunk_registry: Default::default(),
             }
         }
 
@@ -75,29 +186,59 @@ mod sam_os {
         pub fn create_new_account(
             &mut self,
             did: DID,
-            auth_content: AuthContent,
+            auth_pubkey: PubKey,
             did_doc_cid: IpfsCid,
-            root_hash_table: IpfsCid,
+            checkpoint_cid: IpfsCid,
         ) -> Result<()> {
             let user = UserInfo {
-                auth_content,
+                auth_pubkey,
+                auth_nonce: 0,
                 did_doc_cid,
-                root_hash_table,
+                checkpoint_cid,
+                checkpoint_seq: 0,
+                latest_seq: 0,
             };
 
-            self.auth_list.insert(did, &user);
+            self.auth_list.insert(&did, &user);
+            self.env().emit_event(AccountCreated { did });
             Ok(())
         }
 
-        /// Checks if a DID with the provided auth material exists
+        /// Authenticates a DID via a challenge-response signature, mirroring ethkey's
+        /// sign/verify/recover flow. The caller signs `blake2_256(did ++ auth_nonce)`
+        /// off-chain with the key registered for the DID; this message recovers the
+        /// signer's public key from the signature and compares it against the one on
+        /// record. On success the nonce is bumped so the same signature can never be
+        /// replayed, and the DID's latest root hash table checkpoint CID is returned
+        /// (see [`Self::get_hashtable_sync`] for replaying the operations on top of it).
         #[ink(message)]
-        pub fn account_is_auth(&self, did: DID, auth_content: AuthContent) -> (bool, Vec<u8>) {
-            // auth account
-            let did_entry = self.auth_list.get(did);
-            match did_entry {
-                Some(user_info) => (user_info.auth_content == auth_content, user_info.root_hash_table.clone()),
-                None => (false, Default::default()),
+        pub fn authenticate(
+            &mut self,
+            did: DID,
+            signature: Signature,
+        ) -> Result<IpfsCid> {
+            let mut user_info = self.auth_list.get(&did).ok_or(Error::AccountNotFound)?;
+
+            let mut msg = did.clone();
+            msg.extend_from_slice(&user_info.auth_nonce.to_le_bytes());
+
+            let mut msg_hash = <Blake2x256 as HashOutput>::Type::default();
+            self.env().hash_bytes::<Blake2x256>(&msg, &mut msg_hash);
+
+            let mut recovered: PubKey = [0u8; 33];
+            self.env()
+                .ecdsa_recover(&signature, &msg_hash, &mut recovered)
+                .map_err(|_| Error::InvalidSignature)?;
+
+            if recovered != user_info.auth_pubkey {
+                return Err(Error::InvalidSignature);
             }
+
+            user_info.auth_nonce += 1;
+            let checkpoint_cid = user_info.checkpoint_cid.clone();
+            self.auth_list.insert(did, &user_info);
+
+            Ok(checkpoint_cid)
         }
 
         /// Gets important info for IPFS dile syncing
@@ -109,111 +250,219 @@ mod sam_os {
             }
         }
 
-        /// Update hashmap of data
+        /// Appends a new operation CID to the DID's root hash table op log rather
+        /// than overwriting it (Bayou-style), so a syncing client only has to
+        /// replay the tail of operations since its last known seq. Every
+        /// [`CHECKPOINT_INTERVAL`] operations, the appended CID is also recorded
+        /// as a consolidated checkpoint.
         #[ink(message)]
         pub fn update_hashtable(&mut self, cid: IpfsCid, did: DID) {
-            let did_entry = self.auth_list.get(&did).clone();
-            match did_entry {
-                Some(user_info) => {
-                    let mut u_info = user_info.clone();
-                    u_info.root_hash_table = cid;
-                    self.auth_list.insert(did, &u_info);
+            if let Some(mut user_info) = self.auth_list.get(&did) {
+                let seq = user_info.latest_seq + 1;
+                self.hashtable_ops.insert((did.clone(), seq), &cid);
+                user_info.latest_seq = seq;
+
+                if seq % CHECKPOINT_INTERVAL == 0 {
+                    user_info.checkpoint_cid = cid.clone();
+                    user_info.checkpoint_seq = seq;
                 }
-                None => {}
+
+                self.auth_list.insert(&did, &user_info);
+                self.env().emit_event(HashTableUpdated { did, cid, seq });
+            }
+        }
+
+        /// Returns enough information for a client to sync the root hash table:
+        /// the last consolidated checkpoint CID and its seq, plus the latest seq
+        /// appended. The client loads the checkpoint and replays `get_op` for
+        /// every seq in `(checkpoint_seq, latest_seq]`.
+        #[ink(message)]
+        pub fn get_hashtable_sync(&self, did: DID) -> (IpfsCid, u64, u64) {
+            match self.auth_list.get(did) {
+                Some(user_info) => (
+                    user_info.checkpoint_cid,
+                    user_info.checkpoint_seq,
+                    user_info.latest_seq,
+                ),
+                None => (Default::default(), 0, 0),
             }
         }
 
-        /// Updates the metadata of a files
+        /// Fetches a single appended root hash table operation CID by seq.
+        #[ink(message)]
+        pub fn get_op(&self, did: DID, seq: u64) -> IpfsCid {
+            self.hashtable_ops.get((did, seq)).unwrap_or_default()
+        }
+
+        /// Updates the metadata of a file, creating it if `hk` is unknown. `chunks`
+        /// is the file's full, ordered list of content-defined chunks; a chunk not
+        /// yet known on-chain (see [`Self::filter_unknown_chunks`]) is registered
+        /// here with its CID, and a chunk already known has its reference count
+        /// bumped instead of being stored again. On first creation `owner_did` is
+        /// granted full (`READ | WRITE | SHARE`) rights with no expiry; updating an
+        /// existing file requires `owner_did` to hold `WRITE` on it, and leaves the
+        /// grantee list untouched — use [`Self::grant_access`] and
+        /// [`Self::set_rights`] to manage it.
         #[ink(message)]
         pub fn update_file_meta(
             &mut self,
             cid: IpfsCid,
             hk: HashKey,
             metadata: DbMetadata,
-            did_1: DID,
-            did_2: DID,
-            access_bit_1: bool,
-            access_bit_2: bool,
-        ) {
-            let access_bits = [access_bit_1, access_bit_2];
-            let dids = [did_1, did_2];
-            let nonce = match self.files_meta.get(hk) {
+            owner_did: DID,
+            chunks: Vec<(HashKey, IpfsCid)>,
+        ) -> Result<()> {
+            let previous = self.files_meta.get(hk);
+            if previous.is_some() && !self.has_right(&owner_did, hk, RIGHT_WRITE) {
+                return Err(Error::Unauthorized);
+            }
+
+            let nonce = match &previous {
                 Some(meta) => meta.nonce + 1,
                 None => 1,
             };
 
+            if let Some(old_meta) = &previous {
+                self.release_chunks(&old_meta.chunks);
+            }
+            self.register_chunks(&chunks);
+
+            let grantees = match &previous {
+                Some(old_meta) => old_meta.grantees.clone(),
+                None => {
+                    self.access_list.insert(
+                        (owner_did.clone(), hk),
+                        &AccessGrant {
+                            rights: RIGHT_READ | RIGHT_WRITE | RIGHT_SHARE,
+                            expiry: 0,
+                        },
+                    );
+                    self.add_file_key(&owner_did, hk);
+                    let mut grantees = Vec::<DID>::new();
+                    grantees.push(owner_did);
+                    grantees
+                }
+            };
+
             let metadata = FileMeta {
-                access_list: dids.clone(),
-                cid,
+                grantees,
+                cid: cid.clone(),
                 nonce,
                 db_meta: metadata,
+                chunks: chunks.into_iter().map(|(hash, _)| hash).collect(),
             };
 
             // save metadata
             self.files_meta.insert(hk, &metadata);
+            self.env().emit_event(FileUpdated { hk, nonce, cid });
+            Ok(())
+        }
 
-            // set up access list
-            let mut index = 0;
-            for did in dids {
-                // sometimes there can be only one DID exclusive to a file
-                if did != "did:sam:root:apps:xxxxxxxxxxxx".as_bytes().to_vec() {
-                    let current_time = self.access_list.get((did.clone(), hk));
-                    match current_time {
-                        Some(time) => {
-                            self.access_list.insert(
-                                (did.clone(), hk),
-                                if access_bits[index] { &time } else { &0 },
-                            );
-                            // 0 -> access denied
-                        }
-                        None => {
-                            self.access_list.insert((did.clone(), hk), &1); // 1 -> no time limit
-                        }
-                    }
+        /// Grants a new grantee some rights over a file. Only a DID already
+        /// holding `SHARE` on the file may add further grantees.
+        #[ink(message)]
+        pub fn grant_access(
+            &mut self,
+            owner_did: DID,
+            grantee_did: DID,
+            hk: HashKey,
+            rights: u64,
+            expiry: u64,
+        ) -> Result<()> {
+            if !self.has_right(&owner_did, hk, RIGHT_SHARE) {
+                return Err(Error::Unauthorized);
+            }
 
-                    index += 1;
-                    // insert the filekey
-                    let keys = match self.file_keys.get(did.clone()) {
-                        Some(keys) => {
-                            if !keys.contains(&hk) {
-                                let mut new_keys = keys.clone();
-                                new_keys.push(hk);
-                                new_keys
-                            } else {
-                                keys.clone()
-                            }
-                        }
-                        None => {
-                            let mut keys = Vec::<HashKey>::new();
-                            keys.push(hk);
-                            keys
-                        }
-                    };
-
-                    self.file_keys.insert(did, &keys);
-                }
+            let mut file = self.files_meta.get(hk).unwrap_or_default();
+            if !file.grantees.contains(&grantee_did) {
+                file.grantees.push(grantee_did.clone());
+                self.files_meta.insert(hk, &file);
             }
+
+            self.access_list
+                .insert((grantee_did.clone(), hk), &AccessGrant { rights, expiry });
+            self.add_file_key(&grantee_did, hk);
+            self.env().emit_event(AccessGranted {
+                did: grantee_did,
+                hk,
+                rights,
+            });
+            Ok(())
+        }
+
+        /// Changes the rights of a grantee already on a file's grantee list,
+        /// without touching the list itself. Only a DID holding `SHARE` on the
+        /// file may do this, and the grantee must already have been added via
+        /// [`Self::grant_access`].
+        #[ink(message)]
+        pub fn set_rights(
+            &mut self,
+            owner_did: DID,
+            grantee_did: DID,
+            hk: HashKey,
+            rights: u64,
+            expiry: u64,
+        ) -> Result<()> {
+            if !self.has_right(&owner_did, hk, RIGHT_SHARE) {
+                return Err(Error::Unauthorized);
+            }
+
+            let file = self.files_meta.get(hk).unwrap_or_default();
+            if !file.grantees.contains(&grantee_did) {
+                return Err(Error::GranteeNotFound);
+            }
+
+            self.access_list
+                .insert((grantee_did.clone(), hk), &AccessGrant { rights, expiry });
+            self.env().emit_event(AccessGranted {
+                did: grantee_did,
+                hk,
+                rights,
+            });
+            Ok(())
+        }
+
+        /// Records that `did` now has a grant on `hk` in its file key index.
+        fn add_file_key(&mut self, did: &DID, hk: HashKey) {
+            let keys = match self.file_keys.get(did) {
+                Some(keys) => {
+                    if !keys.contains(&hk) {
+                        let mut new_keys = keys.clone();
+                        new_keys.push(hk);
+                        new_keys
+                    } else {
+                        keys
+                    }
+                }
+                None => {
+                    let mut keys = Vec::<HashKey>::new();
+                    keys.push(hk);
+                    keys
+                }
+            };
+
+            self.file_keys.insert(did, &keys);
         }
 
         /// get info about files the DID has access to
         #[ink(message)]
         pub fn get_files_info(&self, did: DID) -> Vec<u8> {
             let mut return_data: Vec<u8> = Vec::new();
-            match self.file_keys.get(did) {
+            match self.file_keys.get(&did) {
                 Some(keys) => {
                     let _ = keys
                         .iter()
+                        .filter(|hk| self.has_access(&did, **hk))
                         .map(|hk| {
                             // get the corresponding file
                             let mut collator = Vec::<u8>::new();
                             let file = self.files_meta.get(hk).unwrap_or_default();
-                            let mut did_1 = file.access_list[0].clone();
-                            let mut did_2 = file.access_list[1].clone();
                             let cid = file.cid.clone();
 
-                            collator.append(&mut did_1);
-                            collator.append(&mut "--".as_bytes().to_vec()); // did separator
-                            collator.append(&mut did_2);
+                            for mut grantee in file.grantees {
+                                collator.append(&mut grantee);
+                                collator.append(&mut "--".as_bytes().to_vec()); // did separator
+                            }
                             collator.append(&mut "##".as_bytes().to_vec()); // separator
 
                             // then the cid
@@ -230,28 +479,25 @@ mod sam_os {
             return_data
         }
 
-        /// get extra info about files the DID has access to
+        /// get extra info about files the DID has access to, including its own
+        /// rights mask for each file
         #[ink(message)]
         pub fn get_files_extra_info(&self, did: DID) -> Vec<(u64, u64, u64)> {
             let mut collator: Vec<(u64, u64, u64)> = Vec::new();
-            match self.file_keys.get(did) {
+            match self.file_keys.get(&did) {
                 Some(keys) => {
                     let _ = keys
                         .iter()
+                        .filter(|hk| self.has_access(&did, **hk))
                         .map(|hk| {
-                            let tuple: (u64, u64, u64);
                             let file = self.files_meta.get(hk).unwrap_or_default();
-                            let did_1 = file.access_list[0].clone();
-
-                            // get the access bits and nonce
-                            let access_bit1 = self
+                            let rights = self
                                 .access_list
-                                .get((did_1.clone(), hk))
+                                .get((did.clone(), hk))
+                                .map(|grant| grant.rights)
                                 .unwrap_or_default();
-                            let nonce = file.nonce;
 
-                            tuple = (nonce, access_bit1, *hk);
-                            collator.push(tuple);
+                            collator.push((file.nonce, rights, *hk));
                         })
                         .collect::<()>();
                 }
@@ -261,24 +507,276 @@ mod sam_os {
             collator
         }
 
-        /// Revokes a DIDs access to a file
+        /// Revokes a DIDs access to a file, or re-grants plain `READ` access with
+        /// the given expiry (milliseconds since epoch, or `0` for no limit). Use
+        /// [`Self::grant_access`] or [`Self::set_rights`] to grant `WRITE`/`SHARE`.
         #[ink(message)]
-        pub fn revoke_access(&mut self, did: DID, hk: HashKey, revoke: bool) {
-            self.access_list
-                .insert((did, hk), if revoke { &0 } else { &1 });
+        pub fn revoke_access(&mut self, did: DID, hk: HashKey, revoke: bool, expiry: u64) {
+            let grant = if revoke {
+                AccessGrant::default()
+            } else {
+                AccessGrant {
+                    rights: RIGHT_READ,
+                    expiry,
+                }
+            };
+            self.access_list.insert((did.clone(), hk), &grant);
+
+            if revoke {
+                self.env().emit_event(AccessRevoked { did, hk });
+            } else {
+                self.env().emit_event(AccessGranted {
+                    did,
+                    hk,
+                    rights: grant.rights,
+                });
+            }
+        }
+
+        /// Whether `did` currently has a live (unexpired) `READ` grant on `hk`.
+        fn has_access(&self, did: &DID, hk: HashKey) -> bool {
+            self.has_right(did, hk, RIGHT_READ)
+        }
+
+        /// Whether `did` currently holds `right` on `hk` and the grant has not
+        /// expired (a stored expiry of `0` means no limit).
+        fn has_right(&self, did: &DID, hk: HashKey, right: u64) -> bool {
+            match self.access_list.get((did.clone(), hk)) {
+                Some(grant) => {
+                    grant.rights & right != 0
+                        && (grant.expiry == 0 || grant.expiry > self.env().block_timestamp())
+                }
+                None => false,
+            }
+        }
+
+        /// Given a list of chunk hashes an uploading client is about to pin to
+        /// IPFS, returns the subset that is not already registered on-chain so
+        /// the client can skip re-pinning chunks that already exist.
+        #[ink(message)]
+        pub fn filter_unknown_chunks(&self, chunks: Vec<HashKey>) -> Vec<HashKey> {
+            chunks
+                .into_iter()
+                .filter(|hash| self.chunk_registry.get(hash).is_none())
+                .collect()
+        }
+
+        /// Registers a file's chunks, reusing and bumping the refcount of any
+        /// chunk already known on-chain instead of storing it again.
+        fn register_chunks(&mut self, chunks: &[(HashKey, IpfsCid)]) {
+            for (hash, cid) in chunks {
+                match self.chunk_registry.get(hash) {
+                    Some((existing_cid, count)) => {
+                        self.chunk_registry.insert(hash, &(existing_cid, count + 1));
+                    }
+                    None => {
+                        self.chunk_registry.insert(hash, &(cid.clone(), 1));
+                    }
+                }
+            }
+        }
+
+        /// Decrements the refcount of each chunk a replaced/deleted file used
+        /// to reference, pruning entries whose refcount reaches zero.
+        fn release_chunks(&mut self, chunks: &[HashKey]) {
+            for hash in chunks {
+                if let Some((cid, count)) = self.chunk_registry.get(hash) {
+                    if count <= 1 {
+                        self.chunk_registry.remove(hash);
+                    } else {
+                        self.chunk_registry.insert(hash, &(cid, count - 1));
+                    }
+                }
+            }
         }
     }
 
-    // #[cfg(test)]
-    // mod tests {
-    //     use super::*;
-
-    //     #[ink::test]
-    //     fn new_works() {
-    //         let mut sam = SamOs::new();
-    //         let did = "did:sam:root:cdsidhfs809s9us0fs9".as_bytes().to_vec();
-    //         sam.create_new_account(did, 4893290392, Vec::new()).ok();
-    //         ink::env::debug_println!("{:#?}", sam);
-    //     }
-    // }
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn did(s: &str) -> DID {
+            s.as_bytes().to_vec()
+        }
+
+        #[ink::test]
+        fn authenticate_rejects_unknown_account() {
+            let mut sam = SamOs::new();
+            assert_eq!(
+                sam.authenticate(did("did:sam:root:unknown"), [0u8; 65]),
+                Err(Error::AccountNotFound)
+            );
+        }
+
+        #[ink::test]
+        fn authenticate_rejects_bad_signature() {
+            let mut sam = SamOs::new();
+            let alice = did("did:sam:root:alice");
+            sam.create_new_account(alice.clone(), [1u8; 33], Vec::new(), Vec::new())
+                .unwrap();
+
+            assert_eq!(
+                sam.authenticate(alice, [0u8; 65]),
+                Err(Error::InvalidSignature)
+            );
+        }
+
+        #[ink::test]
+        fn has_right_respects_expiry() {
+            let mut sam = SamOs::new();
+            let owner = did("did:sam:root:owner");
+            let grantee = did("did:sam:root:grantee");
+            let hk = 42u64;
+
+            sam.update_file_meta(Vec::new(), hk, Vec::new(), owner.clone(), Vec::new())
+                .unwrap();
+            assert!(sam.has_access(&owner, hk));
+
+            sam.grant_access(owner.clone(), grantee.clone(), hk, RIGHT_READ, 100)
+                .unwrap();
+            assert!(sam.has_access(&grantee, hk));
+
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(200);
+            assert!(!sam.has_access(&grantee, hk));
+        }
+
+        #[ink::test]
+        fn update_file_meta_rejects_non_writers() {
+            let mut sam = SamOs::new();
+            let owner = did("did:sam:root:owner");
+            let stranger = did("did:sam:root:stranger");
+            let hk = 7u64;
+
+            sam.update_file_meta(Vec::new(), hk, Vec::new(), owner, Vec::new())
+                .unwrap();
+
+            assert_eq!(
+                sam.update_file_meta(Vec::new(), hk, Vec::new(), stranger, Vec::new()),
+                Err(Error::Unauthorized)
+            );
+        }
+
+        #[ink::test]
+        fn set_rights_rejects_unknown_grantee() {
+            let mut sam = SamOs::new();
+            let owner = did("did:sam:root:owner");
+            let stranger = did("did:sam:root:stranger");
+            let hk = 11u64;
+
+            sam.update_file_meta(Vec::new(), hk, Vec::new(), owner.clone(), Vec::new())
+                .unwrap();
+
+            assert_eq!(
+                sam.set_rights(owner, stranger, hk, RIGHT_READ, 0),
+                Err(Error::GranteeNotFound)
+            );
+        }
+
+        #[ink::test]
+        fn chunk_refcounts_track_sharing_and_prune_to_zero() {
+            let mut sam = SamOs::new();
+            let owner = did("did:sam:root:owner");
+            let shared_hash: HashKey = 1000;
+            let shared_cid = b"shared-cid".to_vec();
+            let a_hash: HashKey = 1001;
+            let a_cid = b"a-cid".to_vec();
+
+            // file 1 references the shared chunk plus one of its own
+            sam.update_file_meta(
+                Vec::new(),
+                1,
+                Vec::new(),
+                owner.clone(),
+                vec![(shared_hash, shared_cid.clone()), (a_hash, a_cid.clone())],
+            )
+            .unwrap();
+
+            // file 2 also references the shared chunk
+            sam.update_file_meta(
+                Vec::new(),
+                2,
+                Vec::new(),
+                owner.clone(),
+                vec![(shared_hash, shared_cid.clone())],
+            )
+            .unwrap();
+
+            assert_eq!(
+                sam.chunk_registry.get(shared_hash),
+                Some((shared_cid.clone(), 2))
+            );
+
+            // file 1 drops the shared chunk, keeping only its own chunk
+            sam.update_file_meta(Vec::new(), 1, Vec::new(), owner.clone(), vec![(a_hash, a_cid)])
+                .unwrap();
+
+            // still referenced by file 2, so the registry entry must survive
+            assert_eq!(
+                sam.chunk_registry.get(shared_hash),
+                Some((shared_cid, 1))
+            );
+
+            // file 2 drops it too, so the refcount hits zero and is pruned
+            sam.update_file_meta(Vec::new(), 2, Vec::new(), owner, Vec::new())
+                .unwrap();
+            assert_eq!(sam.chunk_registry.get(shared_hash), None);
+        }
+
+        #[ink::test]
+        fn filter_unknown_chunks_round_trips_known_chunks() {
+            let mut sam = SamOs::new();
+            let owner = did("did:sam:root:owner");
+            let known: HashKey = 5;
+            let unknown: HashKey = 6;
+
+            sam.update_file_meta(
+                Vec::new(),
+                1,
+                Vec::new(),
+                owner,
+                vec![(known, b"cid".to_vec())],
+            )
+            .unwrap();
+
+            assert_eq!(sam.filter_unknown_chunks(vec![known, unknown]), vec![unknown]);
+        }
+
+        #[ink::test]
+        fn hashtable_sync_checkpoints_every_interval_and_replays_tail() {
+            let mut sam = SamOs::new();
+            let alice = did("did:sam:root:alice");
+            sam.create_new_account(alice.clone(), [1u8; 33], Vec::new(), Vec::new())
+                .unwrap();
+
+            let total_ops = CHECKPOINT_INTERVAL + 5;
+            for seq in 1..=total_ops {
+                let cid = seq.to_be_bytes().to_vec();
+                sam.update_hashtable(cid, alice.clone());
+            }
+
+            let (checkpoint_cid, checkpoint_seq, latest_seq) = sam.get_hashtable_sync(alice.clone());
+            assert_eq!(checkpoint_seq, CHECKPOINT_INTERVAL);
+            assert_eq!(latest_seq, total_ops);
+            assert_eq!(checkpoint_cid, CHECKPOINT_INTERVAL.to_be_bytes().to_vec());
+
+            // client replays (checkpoint_seq, latest_seq] via get_op
+            let replay_seq = checkpoint_seq + 1;
+            assert_eq!(sam.get_op(alice, replay_seq), replay_seq.to_be_bytes().to_vec());
+        }
+
+        #[ink::test]
+        fn create_new_account_emits_account_created_event() {
+            let mut sam = SamOs::new();
+            let alice = did("did:sam:root:alice");
+            sam.create_new_account(alice.clone(), [1u8; 33], Vec::new(), Vec::new())
+                .unwrap();
+
+            let events = ink::env::test::recorded_events().collect::<Vec<_>>();
+            assert_eq!(events.len(), 1);
+
+            let decoded: AccountCreated =
+                scale::Decode::decode(&mut &events[0].data[..]).unwrap();
+            assert_eq!(decoded.did, alice);
+        }
+    }
 }